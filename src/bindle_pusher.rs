@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use anyhow::Context;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::bindle_utils;
+
+/// Default number of parcels to upload concurrently when the caller doesn't
+/// override it with `--upload-concurrency`.
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+
+/// Pushes a staged bindle (invoice plus parcels) from `destination` to the
+/// Bindle server at `bindle_url`. Parcels the server already holds (matched by
+/// SHA-256) are skipped, so repeated pushes of a mostly-unchanged bindle only
+/// transfer what actually changed. Up to `upload_concurrency` parcels are
+/// streamed to the server in parallel; the first upload failure cancels the
+/// rest and is returned.
+pub async fn push_all(
+    destination: impl AsRef<Path>,
+    id: &bindle::Id,
+    bindle_url: &str,
+    upload_concurrency: usize,
+) -> anyhow::Result<()> {
+    let destination = destination.as_ref();
+    let invoice = bindle_utils::read_invoice(destination)
+        .with_context(|| format!("Can't read staged invoice for {}", id))?;
+    let client = bindle::client::Client::new(bindle_url)?;
+
+    client
+        .create_invoice(&invoice)
+        .await
+        .with_context(|| format!("Can't create invoice {} on {}", id, bindle_url))?;
+
+    let parcels = invoice.parcel.unwrap_or_default();
+    let mut existence = Vec::with_capacity(parcels.len());
+    for parcel in &parcels {
+        existence.push(client.parcel_exists(&parcel.label.sha256).await?);
+    }
+    let missing = select_missing(parcels, existence);
+
+    futures::stream::iter(missing.into_iter().map(|parcel| {
+        let client = client.clone();
+        async move {
+            let data = tokio::fs::read(bindle_utils::parcel_path(destination, &parcel.label.sha256)).await?;
+            client
+                .create_parcel(id, &parcel.label.sha256, data)
+                .await
+                .with_context(|| format!("Can't upload parcel {} for {}", parcel.label.name, id))
+        }
+    }))
+    .buffer_unordered(upload_concurrency.max(1))
+    .try_for_each(|_| async { Ok(()) })
+    .await
+}
+
+/// Keeps only the parcels whose corresponding entry in `existence` (indices
+/// lining up positionally with `parcels`) is `false`, i.e. the ones the
+/// server doesn't already have.
+fn select_missing(parcels: Vec<bindle::Parcel>, existence: Vec<bool>) -> Vec<bindle::Parcel> {
+    parcels
+        .into_iter()
+        .zip(existence)
+        .filter_map(|(parcel, exists)| if exists { None } else { Some(parcel) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parcel(name: &str, sha256: &str) -> bindle::Parcel {
+        bindle::Parcel {
+            label: bindle::Label {
+                sha256: sha256.to_owned(),
+                name: name.to_owned(),
+                size: 0,
+                media_type: "application/octet-stream".to_owned(),
+                ..Default::default()
+            },
+            conditions: None,
+        }
+    }
+
+    #[test]
+    fn select_missing_keeps_only_absent_parcels() {
+        let parcels = vec![parcel("a.txt", "sha-a"), parcel("b.txt", "sha-b"), parcel("c.txt", "sha-c")];
+        let existence = vec![true, false, false];
+
+        let missing = select_missing(parcels, existence);
+
+        let names: Vec<&str> = missing.iter().map(|p| p.label.name.as_str()).collect();
+        assert_eq!(names, vec!["b.txt", "c.txt"]);
+    }
+
+    #[test]
+    fn select_missing_is_empty_when_all_present() {
+        let parcels = vec![parcel("a.txt", "sha-a")];
+        let existence = vec![true];
+
+        assert!(select_missing(parcels, existence).is_empty());
+    }
+
+    #[test]
+    fn select_missing_keeps_everything_when_none_present() {
+        let parcels = vec![parcel("a.txt", "sha-a"), parcel("b.txt", "sha-b")];
+        let existence = vec![false, false];
+
+        assert_eq!(select_missing(parcels, existence).len(), 2);
+    }
+}
@@ -0,0 +1,30 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+/// Name of the file, within a staging directory, that holds the serialized invoice.
+pub const INVOICE_FILE: &str = "invoice.toml";
+
+/// Name of the subdirectory, within a staging directory, that holds parcel payloads.
+pub const PARCELS_DIR: &str = "parcels";
+
+pub fn sha256_digest(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Reads and parses the invoice staged at `destination`.
+pub fn read_invoice(destination: impl AsRef<Path>) -> anyhow::Result<bindle::Invoice> {
+    let invoice_path = destination.as_ref().join(INVOICE_FILE);
+    let raw = std::fs::read_to_string(&invoice_path)
+        .with_context(|| format!("Failed to read invoice at {}", invoice_path.display()))?;
+    toml::from_str(&raw)
+        .with_context(|| format!("Failed to parse invoice at {}", invoice_path.display()))
+}
+
+/// The path, within a staging directory, at which a parcel's payload is staged.
+pub fn parcel_path(destination: impl AsRef<Path>, sha256: &str) -> PathBuf {
+    destination.as_ref().join(PARCELS_DIR).join(sha256)
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use fs2::FileExt;
+
+use crate::bindle_utils;
+
+/// Advisory lock file used to stop two `hippo` invocations from interleaving
+/// writes to the same staging directory.
+const LOCK_FILE: &str = ".hippo-lock";
+
+/// Configuration for signing a generated invoice before it's staged.
+#[derive(Clone)]
+pub struct SigningConfig {
+    pub keyfile: PathBuf,
+    pub role: bindle::SignatureRole,
+}
+
+impl SigningConfig {
+    pub fn parse_role(text: &str) -> anyhow::Result<bindle::SignatureRole> {
+        match text {
+            "creator" => Ok(bindle::SignatureRole::Creator),
+            "approver" => Ok(bindle::SignatureRole::Approver),
+            "host" => Ok(bindle::SignatureRole::Host),
+            "proxy" => Ok(bindle::SignatureRole::Proxy),
+            other => Err(anyhow::anyhow!("Unknown signing role: {}", other)),
+        }
+    }
+}
+
+pub struct BindleWriter {
+    source_dir: PathBuf,
+    destination: PathBuf,
+    signing: Option<SigningConfig>,
+}
+
+impl BindleWriter {
+    pub fn new(source_dir: impl AsRef<Path>, destination: impl AsRef<Path>, signing: Option<SigningConfig>) -> Self {
+        Self {
+            source_dir: source_dir.as_ref().to_path_buf(),
+            destination: destination.as_ref().to_path_buf(),
+            signing,
+        }
+    }
+
+    /// Stages `invoice` and all of its parcels under the destination directory,
+    /// ready to be pushed to a Bindle server or served directly. If a signing
+    /// key was configured, a detached signature is attached to the invoice
+    /// before it's written. Holds an advisory lock on the destination for the
+    /// duration of the write, failing fast if another `hippo` process already
+    /// holds it, so two overlapping invocations can't interleave writes and
+    /// leave a half-written bindle behind.
+    pub async fn write(&self, invoice: &bindle::Invoice) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.destination)
+            .await
+            .with_context(|| format!("Can't create staging directory {}", self.destination.display()))?;
+        let lock = self.try_lock()?;
+
+        let parcels_dir = self.destination.join(bindle_utils::PARCELS_DIR);
+        tokio::fs::create_dir_all(&parcels_dir)
+            .await
+            .with_context(|| format!("Can't create parcels directory {}", parcels_dir.display()))?;
+
+        for parcel in invoice.parcel.iter().flatten() {
+            let source_path = self.source_dir.join(&parcel.label.name);
+            let dest_path = bindle_utils::parcel_path(&self.destination, &parcel.label.sha256);
+            tokio::fs::copy(&source_path, &dest_path).await.with_context(|| {
+                format!(
+                    "Can't stage parcel {} from {}",
+                    parcel.label.name,
+                    source_path.display()
+                )
+            })?;
+        }
+
+        let mut invoice = invoice.clone();
+        if let Some(signing) = &self.signing {
+            let keypair = bindle::SecretKeyEntry::load_first_from_file(&signing.keyfile)
+                .with_context(|| format!("Can't load signing key {}", signing.keyfile.display()))?;
+            invoice
+                .sign(signing.role.clone(), &keypair)
+                .with_context(|| "Can't sign invoice")?;
+        }
+
+        let invoice_path = self.destination.join(bindle_utils::INVOICE_FILE);
+        let raw = toml::to_string_pretty(&invoice)?;
+        let result = tokio::fs::write(&invoice_path, raw)
+            .await
+            .with_context(|| format!("Can't write invoice to {}", invoice_path.display()));
+
+        drop(lock);
+        result
+    }
+
+    /// Takes a non-blocking exclusive lock on the staging directory, failing
+    /// fast rather than waiting if another process already holds it.
+    fn try_lock(&self) -> anyhow::Result<std::fs::File> {
+        let lock_path = self.destination.join(LOCK_FILE);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Can't open lock file {}", lock_path.display()))?;
+        file.try_lock_exclusive().map_err(|_| {
+            anyhow::anyhow!("staging directory {} is in use by another hippo process", self.destination.display())
+        })?;
+        Ok(file)
+    }
+}
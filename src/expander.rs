@@ -0,0 +1,366 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::hippofacts::{HippoFacts, HippoFactsEntry};
+
+#[derive(Clone, Copy)]
+pub enum InvoiceVersioning {
+    Dev,
+    Production,
+}
+
+impl InvoiceVersioning {
+    pub fn parse(text: &str) -> Self {
+        if text == "production" {
+            InvoiceVersioning::Production
+        } else {
+            InvoiceVersioning::Dev
+        }
+    }
+}
+
+pub struct ExpansionContext {
+    pub relative_to: PathBuf,
+    pub invoice_versioning: InvoiceVersioning,
+    pub external_invoices: HashMap<bindle::Id, bindle::Invoice>,
+}
+
+/// Expands a parsed HIPPOFACTS spec into a full Bindle invoice: resolves each
+/// entry's file globs (relative to `ctx.relative_to`) into parcels, and folds
+/// in any prefetched external invoices referenced by `external`.
+pub fn expand(spec: &HippoFacts, ctx: &ExpansionContext) -> anyhow::Result<bindle::Invoice> {
+    let mut parcels = vec![];
+
+    // An entry that other entries `requires` gets its own group; the entries
+    // requiring it declare that group in their parcels' `requires` condition.
+    // This is the only place `requires` has any effect on the built invoice -
+    // `validator::validate` just checks the names resolve.
+    let required_by_others: HashSet<&str> = spec
+        .entries
+        .iter()
+        .flat_map(|entry| entry.requires.iter().flatten())
+        .map(String::as_str)
+        .collect();
+
+    for entry in &spec.entries {
+        let mut entry_parcels = if let Some(external) = entry.external_ref() {
+            let external_invoice = ctx.external_invoices.get(&external.bindle_id).ok_or_else(|| {
+                anyhow::anyhow!("No prefetched invoice for external reference {}", external.bindle_id)
+            })?;
+            external_invoice.parcel.clone().unwrap_or_default()
+        } else {
+            expand_entry(entry, &ctx.relative_to)?
+        };
+        // Applies regardless of whether this entry's parcels came from a
+        // local glob or a prefetched external invoice, so an entry that's
+        // required by another (or that itself `requires` something) is
+        // wired up the same way either way.
+        apply_conditions(entry, &required_by_others, &mut entry_parcels);
+        parcels.extend(entry_parcels);
+    }
+
+    let id = bindle::Id::try_from(format!(
+        "{}/{}",
+        spec.bindle.name,
+        versioned(&spec.bindle.version, ctx.invoice_versioning)
+    ))?;
+
+    Ok(bindle::Invoice {
+        bindle_version: "1.0.0".to_owned(),
+        yanked: None,
+        bindle: bindle::BindleSpec {
+            id,
+            description: None,
+            authors: None,
+        },
+        annotations: spec.annotations.clone(),
+        parcel: if parcels.is_empty() { None } else { Some(parcels) },
+        group: groups_for(spec, &required_by_others),
+        signature: None,
+    })
+}
+
+/// Marks `entry`'s parcels as belonging to the group named after it (if some
+/// other entry requires it) and/or as requiring the groups named after the
+/// entries it itself `requires`. Applies the same way whether `parcels` came
+/// from a local glob or a prefetched external invoice.
+fn apply_conditions(entry: &HippoFactsEntry, required_by_others: &HashSet<&str>, parcels: &mut [bindle::Parcel]) {
+    let member_of = required_by_others
+        .contains(entry.name.as_str())
+        .then(|| vec![entry.name.clone()]);
+    let requires = entry.requires.clone();
+
+    if member_of.is_none() && requires.is_none() {
+        return;
+    }
+
+    for parcel in parcels.iter_mut() {
+        parcel.conditions = Some(bindle::Condition {
+            member_of: member_of.clone(),
+            requires: requires.clone(),
+        });
+    }
+}
+
+/// One Bindle group per entry that some other entry `requires`, so that
+/// `conditions.requires` on the dependent parcels names a group that actually
+/// exists on the invoice.
+fn groups_for(spec: &HippoFacts, required_by_others: &HashSet<&str>) -> Option<Vec<bindle::Group>> {
+    let groups: Vec<bindle::Group> = spec
+        .entries
+        .iter()
+        .filter(|entry| required_by_others.contains(entry.name.as_str()))
+        .map(|entry| bindle::Group {
+            name: entry.name.clone(),
+            required: Some(false),
+            satisfied_by: None,
+        })
+        .collect();
+
+    if groups.is_empty() {
+        None
+    } else {
+        Some(groups)
+    }
+}
+
+fn expand_entry(entry: &HippoFactsEntry, relative_to: &std::path::Path) -> anyhow::Result<Vec<bindle::Parcel>> {
+    let mut parcels = vec![];
+
+    for pattern in entry.files.iter().flatten() {
+        let full_pattern = relative_to.join(pattern);
+        for found in glob::glob(&full_pattern.to_string_lossy())? {
+            let path = found?;
+            if !path.is_file() {
+                continue;
+            }
+            let bytes = std::fs::read(&path)?;
+            let sha256 = crate::bindle_utils::sha256_digest(&bytes);
+            let name = path
+                .file_name()
+                .ok_or_else(|| anyhow::anyhow!("Entry {} matched a path with no file name", entry.name))?
+                .to_string_lossy()
+                .to_string();
+
+            parcels.push(bindle::Parcel {
+                label: bindle::Label {
+                    sha256,
+                    name,
+                    size: bytes.len() as u64,
+                    media_type: mime_guess::from_path(&path).first_or_octet_stream().to_string(),
+                    ..Default::default()
+                },
+                conditions: None,
+            });
+        }
+    }
+
+    Ok(parcels)
+}
+
+/// The distinct, non-glob root paths referenced by an artifacts spec's
+/// entries: the directory before the first glob component, or the file itself
+/// for a pattern with no glob characters. Used by `watch` mode to know what to keep an eye on besides the
+/// HIPPOFACTS file itself.
+pub fn glob_roots(spec: &HippoFacts, relative_to: &std::path::Path) -> Vec<PathBuf> {
+    let mut roots = vec![];
+    for entry in &spec.entries {
+        for pattern in entry.files.iter().flatten() {
+            let root = non_glob_prefix(&relative_to.join(pattern));
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+    }
+    roots
+}
+
+fn non_glob_prefix(path: &std::path::Path) -> PathBuf {
+    let mut root = PathBuf::new();
+    for component in path.components() {
+        let part = component.as_os_str().to_string_lossy();
+        if part.contains('*') || part.contains('?') || part.contains('[') {
+            break;
+        }
+        root.push(component);
+    }
+    root
+}
+
+fn versioned(version: &str, versioning: InvoiceVersioning) -> String {
+    match versioning {
+        InvoiceVersioning::Production => version.to_owned(),
+        InvoiceVersioning::Dev => format!("{}-dev+{}", version, chrono::Utc::now().format("%Y%m%d%H%M%S")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hippofacts::{BindleSpec, ExternalRef, HippoFactsEntry};
+
+    fn entry(name: &str) -> HippoFactsEntry {
+        HippoFactsEntry {
+            name: name.to_owned(),
+            route: None,
+            files: None,
+            external: None,
+            requires: None,
+        }
+    }
+
+    fn parcel(name: &str) -> bindle::Parcel {
+        bindle::Parcel {
+            label: bindle::Label {
+                sha256: format!("sha-{}", name),
+                name: name.to_owned(),
+                size: 0,
+                media_type: "application/octet-stream".to_owned(),
+                ..Default::default()
+            },
+            conditions: None,
+        }
+    }
+
+    #[test]
+    fn apply_conditions_sets_member_of_for_a_required_entry() {
+        let api = entry("api");
+        let required_by_others: HashSet<&str> = ["api"].into_iter().collect();
+        let mut parcels = vec![parcel("api.wasm")];
+
+        apply_conditions(&api, &required_by_others, &mut parcels);
+
+        let conditions = parcels[0].conditions.as_ref().unwrap();
+        assert_eq!(conditions.member_of, Some(vec!["api".to_owned()]));
+        assert_eq!(conditions.requires, None);
+    }
+
+    #[test]
+    fn apply_conditions_sets_requires_for_a_requiring_entry() {
+        let mut web = entry("web");
+        web.requires = Some(vec!["api".to_owned()]);
+        let mut parcels = vec![parcel("web.wasm")];
+
+        apply_conditions(&web, &HashSet::new(), &mut parcels);
+
+        let conditions = parcels[0].conditions.as_ref().unwrap();
+        assert_eq!(conditions.requires, Some(vec!["api".to_owned()]));
+        assert_eq!(conditions.member_of, None);
+    }
+
+    #[test]
+    fn apply_conditions_is_indifferent_to_whether_the_entry_is_external() {
+        let mut api = entry("api");
+        api.external = Some(ExternalRef {
+            bindle_id: bindle::Id::try_from("example.com/api/1.0.0".to_owned()).unwrap(),
+        });
+        let required_by_others: HashSet<&str> = ["api"].into_iter().collect();
+        let mut parcels = vec![parcel("api.wasm")];
+
+        apply_conditions(&api, &required_by_others, &mut parcels);
+
+        assert_eq!(
+            parcels[0].conditions.as_ref().unwrap().member_of,
+            Some(vec!["api".to_owned()])
+        );
+    }
+
+    #[test]
+    fn groups_for_emits_one_group_per_required_entry() {
+        let spec = HippoFacts {
+            bindle: BindleSpec {
+                name: "test".to_owned(),
+                version: "0.1.0".to_owned(),
+            },
+            annotations: None,
+            entries: vec![entry("web"), entry("api")],
+        };
+        let required_by_others: HashSet<&str> = ["api"].into_iter().collect();
+
+        let groups = groups_for(&spec, &required_by_others).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name, "api");
+    }
+
+    #[test]
+    fn groups_for_is_none_when_nothing_is_required() {
+        let spec = HippoFacts {
+            bindle: BindleSpec {
+                name: "test".to_owned(),
+                version: "0.1.0".to_owned(),
+            },
+            annotations: None,
+            entries: vec![entry("web")],
+        };
+
+        assert!(groups_for(&spec, &HashSet::new()).is_none());
+    }
+
+    #[test]
+    fn expand_wires_conditions_for_an_external_entry_required_by_a_local_one() {
+        // Regression test: apply_conditions used to only run for locally-globbed
+        // entries, so an external entry's copied-in parcels never got their
+        // member_of set even though groups_for still emitted a group for them.
+        let external_id = bindle::Id::try_from("example.com/api/1.0.0".to_owned()).unwrap();
+
+        let mut api = entry("api");
+        api.external = Some(ExternalRef {
+            bindle_id: external_id.clone(),
+        });
+
+        let mut web = entry("web");
+        web.requires = Some(vec!["api".to_owned()]);
+
+        let spec = HippoFacts {
+            bindle: BindleSpec {
+                name: "test".to_owned(),
+                version: "0.1.0".to_owned(),
+            },
+            annotations: None,
+            entries: vec![web, api],
+        };
+
+        let mut external_invoices = HashMap::new();
+        external_invoices.insert(
+            external_id.clone(),
+            bindle::Invoice {
+                bindle_version: "1.0.0".to_owned(),
+                yanked: None,
+                bindle: bindle::BindleSpec {
+                    id: external_id,
+                    description: None,
+                    authors: None,
+                },
+                annotations: None,
+                parcel: Some(vec![parcel("api.wasm")]),
+                group: None,
+                signature: None,
+            },
+        );
+
+        let ctx = ExpansionContext {
+            relative_to: std::env::temp_dir(),
+            invoice_versioning: InvoiceVersioning::Production,
+            external_invoices,
+        };
+
+        let invoice = expand(&spec, &ctx).unwrap();
+
+        let api_parcel = invoice
+            .parcel
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|p| p.label.name == "api.wasm")
+            .unwrap();
+        assert_eq!(
+            api_parcel.conditions.as_ref().unwrap().member_of,
+            Some(vec!["api".to_owned()])
+        );
+
+        let groups = invoice.group.as_ref().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name, "api");
+    }
+}
@@ -0,0 +1,31 @@
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    pub url: String,
+    pub danger_accept_invalid_certs: bool,
+    pub username: String,
+    pub password: String,
+}
+
+/// Tells Hippo that a new bindle is available so it can schedule the application.
+pub async fn register(id: &bindle::Id, connection: &ConnectionInfo) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(connection.danger_accept_invalid_certs)
+        .build()?;
+
+    let response = client
+        .post(format!("{}/api/apps", connection.url.trim_end_matches('/')))
+        .basic_auth(&connection.username, Some(&connection.password))
+        .json(&serde_json::json!({ "bindleId": id.to_string() }))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Hippo rejected the registration for {}: {}",
+            id,
+            response.status()
+        ))
+    }
+}
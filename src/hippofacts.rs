@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HippoFacts {
+    pub bindle: BindleSpec,
+    #[serde(default)]
+    pub annotations: Option<HashMap<String, String>>,
+    #[serde(rename = "handler", default)]
+    pub entries: Vec<HippoFactsEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BindleSpec {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HippoFactsEntry {
+    pub name: String,
+    pub route: Option<String>,
+    pub files: Option<Vec<String>>,
+    pub external: Option<ExternalRef>,
+    /// Names of other entries in this spec that must be present for this one
+    /// to be usable. Expanded into a Bindle group per required entry and a
+    /// `requires` condition on this entry's parcels (see `expander::expand`).
+    pub requires: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExternalRef {
+    pub bindle_id: bindle::Id,
+}
+
+impl HippoFacts {
+    pub fn read_from(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Can't read artifacts spec {}", path.display()))?;
+        toml::from_str(&raw)
+            .with_context(|| format!("Can't parse artifacts spec {}", path.display()))
+    }
+}
+
+impl HippoFactsEntry {
+    pub fn external_ref(&self) -> Option<&ExternalRef> {
+        self.external.as_ref()
+    }
+}
@@ -0,0 +1,258 @@
+//! Core library behind the `hippo` CLI: expands a HIPPOFACTS spec into a
+//! Bindle invoice, stages it, and optionally pushes it to a Bindle server and
+//! registers it with Hippo. The CLI binary is a thin `clap` front-end over
+//! [`BuildRequest`].
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub use bindle_writer::SigningConfig;
+pub use expander::InvoiceVersioning;
+pub use hippo_notifier::ConnectionInfo;
+pub use server::{serve, TlsConfig};
+pub use validator::{Diagnostic, Severity};
+
+mod bindle_pusher;
+mod bindle_utils;
+mod bindle_writer;
+mod expander;
+mod hippo_notifier;
+mod hippofacts;
+mod server;
+mod validator;
+
+use bindle_writer::BindleWriter;
+use expander::ExpansionContext;
+use hippofacts::{HippoFacts, HippoFactsEntry};
+
+#[derive(Debug, thiserror::Error)]
+pub enum HippoError {
+    #[error("artifacts spec not found: {}", .0.display())]
+    SpecNotFound(PathBuf),
+    #[error("can't read artifacts spec: {0}")]
+    ReadSpec(#[source] anyhow::Error),
+    #[error("can't prefetch external invoices: {0}")]
+    Prefetch(#[source] anyhow::Error),
+    #[error("can't expand artifacts spec: {0}")]
+    Expand(#[source] anyhow::Error),
+    #[error("can't stage bindle: {0}")]
+    Write(#[source] anyhow::Error),
+    #[error("can't push to Bindle server: {0}")]
+    Push(#[source] anyhow::Error),
+    #[error("can't notify Hippo: {0}")]
+    Notify(#[source] anyhow::Error),
+}
+
+/// Whether and where to push the generated bindle.
+#[derive(Clone)]
+pub enum BindleSettings {
+    NoPush(Option<String>),
+    Push(String),
+}
+
+impl BindleSettings {
+    pub fn bindle_url(&self) -> Option<String> {
+        match self {
+            Self::NoPush(opt) => opt.clone(),
+            Self::Push(url) => Some(url.clone()),
+        }
+    }
+}
+
+/// Outcome of running a [`BuildRequest`].
+pub struct BuildResult {
+    pub id: bindle::Id,
+    pub parcels: Vec<String>,
+    pub pushed: bool,
+    pub notified: bool,
+}
+
+/// Builds up everything needed to expand, stage, and (optionally) publish a
+/// HIPPOFACTS spec, then runs it with [`BuildRequest::run`].
+pub struct BuildRequest {
+    source: PathBuf,
+    destination: PathBuf,
+    invoice_versioning: InvoiceVersioning,
+    bindle_settings: BindleSettings,
+    connection: Option<ConnectionInfo>,
+    upload_concurrency: usize,
+    signing: Option<SigningConfig>,
+    trusted_keys: Option<PathBuf>,
+}
+
+impl BuildRequest {
+    pub fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        Self {
+            source: source.into(),
+            destination: destination.into(),
+            invoice_versioning: InvoiceVersioning::Dev,
+            bindle_settings: BindleSettings::NoPush(None),
+            connection: None,
+            upload_concurrency: bindle_pusher::DEFAULT_UPLOAD_CONCURRENCY,
+            signing: None,
+            trusted_keys: None,
+        }
+    }
+
+    pub fn invoice_versioning(mut self, invoice_versioning: InvoiceVersioning) -> Self {
+        self.invoice_versioning = invoice_versioning;
+        self
+    }
+
+    pub fn bindle_settings(mut self, bindle_settings: BindleSettings) -> Self {
+        self.bindle_settings = bindle_settings;
+        self
+    }
+
+    pub fn connect_to(mut self, connection: ConnectionInfo) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    pub fn upload_concurrency(mut self, upload_concurrency: usize) -> Self {
+        self.upload_concurrency = upload_concurrency;
+        self
+    }
+
+    pub fn sign_with(mut self, signing: SigningConfig) -> Self {
+        self.signing = Some(signing);
+        self
+    }
+
+    pub fn trust_keys(mut self, trusted_keys: impl Into<PathBuf>) -> Self {
+        self.trusted_keys = Some(trusted_keys.into());
+        self
+    }
+
+    /// Expands the spec, stages the resulting bindle, and pushes/notifies if
+    /// configured to.
+    pub async fn run(self) -> Result<BuildResult, HippoError> {
+        let spec = HippoFacts::read_from(&self.source).map_err(HippoError::ReadSpec)?;
+
+        let source_dir = self
+            .source
+            .parent()
+            .ok_or_else(|| HippoError::SpecNotFound(self.source.clone()))?
+            .to_path_buf();
+
+        let external_invoices = prefetch_required_invoices(
+            &spec,
+            self.bindle_settings.bindle_url(),
+            self.trusted_keys.as_deref(),
+        )
+        .await
+        .map_err(HippoError::Prefetch)?;
+
+        let expansion_context = ExpansionContext {
+            relative_to: source_dir.clone(),
+            invoice_versioning: self.invoice_versioning,
+            external_invoices,
+        };
+
+        let invoice = expander::expand(&spec, &expansion_context).map_err(HippoError::Expand)?;
+
+        let writer = BindleWriter::new(&source_dir, &self.destination, self.signing);
+        writer.write(&invoice).await.map_err(HippoError::Write)?;
+
+        let mut pushed = false;
+        let mut notified = false;
+        if let BindleSettings::Push(url) = &self.bindle_settings {
+            bindle_pusher::push_all(&self.destination, &invoice.bindle.id, url, self.upload_concurrency)
+                .await
+                .map_err(HippoError::Push)?;
+            pushed = true;
+
+            if let Some(connection) = &self.connection {
+                hippo_notifier::register(&invoice.bindle.id, connection)
+                    .await
+                    .map_err(HippoError::Notify)?;
+                notified = true;
+            }
+        }
+
+        let parcels = invoice
+            .parcel
+            .iter()
+            .flatten()
+            .map(|p| p.label.name.clone())
+            .collect();
+
+        Ok(BuildResult {
+            id: invoice.bindle.id,
+            parcels,
+            pushed,
+            notified,
+        })
+    }
+}
+
+/// Lints the artifacts spec at `source` without expanding or staging it.
+/// Returns every diagnostic found rather than stopping at the first one.
+pub fn validate(source: impl AsRef<Path>, bindle_url: Option<&str>) -> anyhow::Result<Vec<Diagnostic>> {
+    let source = source.as_ref();
+    let source_dir = source
+        .parent()
+        .ok_or_else(|| anyhow::Error::msg("Can't establish source directory"))?;
+    let spec = HippoFacts::read_from(source)?;
+    Ok(validator::validate(&spec, source_dir, bindle_url))
+}
+
+/// The distinct, non-glob root paths (directories, or files for patterns with
+/// no glob characters) an artifacts spec at `source` references. Exposed for CLI modes (like `watch`) that need to know what to
+/// keep an eye on besides the HIPPOFACTS file itself.
+pub fn glob_roots(source: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+    let source = source.as_ref();
+    let source_dir = source
+        .parent()
+        .ok_or_else(|| anyhow::Error::msg("Can't establish source directory"))?;
+    let spec = HippoFacts::read_from(source)?;
+    Ok(expander::glob_roots(&spec, source_dir))
+}
+
+async fn prefetch_required_invoices(
+    hippofacts: &HippoFacts,
+    bindle_url: Option<String>,
+    trusted_keys: Option<&Path>,
+) -> anyhow::Result<HashMap<bindle::Id, bindle::Invoice>> {
+    use anyhow::Context;
+
+    let mut map = HashMap::new();
+
+    let external_refs: Vec<bindle::Id> = hippofacts
+        .entries
+        .iter()
+        .flat_map(external_bindle_id)
+        .collect();
+    if external_refs.is_empty() {
+        return Ok(map);
+    }
+
+    let base_url = bindle_url.as_ref().ok_or_else(|| {
+        anyhow::anyhow!("Spec file contains external references but Bindle server URL is not set")
+    })?;
+    let client = bindle::client::Client::new(base_url)?;
+
+    let keyring = trusted_keys
+        .map(bindle::KeyRing::from_file)
+        .transpose()
+        .with_context(|| "Can't read trusted keys file")?;
+
+    for external_ref in external_refs {
+        let invoice = client.get_yanked_invoice(&external_ref).await?;
+        if let Some(keyring) = &keyring {
+            if invoice.signature.is_none() {
+                return Err(anyhow::anyhow!("External invoice {} is unsigned", external_ref));
+            }
+            invoice
+                .verify(keyring.clone())
+                .with_context(|| format!("External invoice {} failed signature verification", external_ref))?;
+        }
+        map.insert(external_ref, invoice);
+    }
+
+    Ok(map)
+}
+
+fn external_bindle_id(entry: &HippoFactsEntry) -> Option<bindle::Id> {
+    entry.external_ref().map(|ext| ext.bindle_id.clone())
+}
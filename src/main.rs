@@ -1,15 +1,5 @@
-use std::collections::HashMap;
-
-use bindle_writer::BindleWriter;
-use expander::{ExpansionContext, InvoiceVersioning};
-use hippofacts::{HippoFacts, HippoFactsEntry};
-
-mod bindle_pusher;
-mod bindle_utils;
-mod bindle_writer;
-mod expander;
-mod hippo_notifier;
-mod hippofacts;
+use anyhow::Context;
+use hippo_core::{BindleSettings, BuildRequest, ConnectionInfo, InvoiceVersioning, SigningConfig};
 
 const ARG_HIPPOFACTS: &str = "hippofacts_path";
 const ARG_STAGING_DIR: &str = "output_dir";
@@ -21,10 +11,32 @@ const ARG_HIPPO_USERNAME: &str = "hippo_username";
 const ARG_HIPPO_PASSWORD: &str = "hippo_password";
 const ARG_ACTION: &str = "action";
 const ARG_INSECURE: &str = "insecure";
+const ARG_UPLOAD_CONCURRENCY: &str = "upload_concurrency";
+const ARG_SIGN_WITH: &str = "sign_with";
+const ARG_SIGNING_ROLE: &str = "signing_role";
+const ARG_TRUSTED_KEYS: &str = "trusted_keys";
+const ARG_LISTEN: &str = "listen";
+const ARG_TLS_CERT: &str = "tls_cert";
+const ARG_TLS_KEY: &str = "tls_key";
 
 const ACTION_ALL: &str = "all";
 const ACTION_BINDLE: &str = "bindle";
 const ACTION_PREPARE: &str = "prepare";
+const ACTION_WATCH: &str = "watch";
+const ACTION_VALIDATE: &str = "validate";
+const ACTION_SERVE: &str = "serve";
+
+const SUBCOMMAND_LOGIN: &str = "login";
+const ARG_LOGIN_PASSWORD: &str = "password";
+
+/// Service name under which Hippo credentials are stored in the OS keyring,
+/// keyed by (this, hippo_url, hippo_username).
+const KEYRING_SERVICE: &str = "hippo-cli";
+
+/// How long to let file change events pile up before reacting, so that a
+/// save-storm from an editor or `cargo build` triggers one rebuild instead of
+/// several.
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -32,6 +44,32 @@ async fn main() -> anyhow::Result<()> {
         .version(env!("CARGO_PKG_VERSION"))
         .author("Deis Labs")
         .about("Expands Hippo artifacts files for upload to application storage")
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            clap::App::new(SUBCOMMAND_LOGIN)
+                .about("Store Hippo credentials in the OS keyring")
+                .arg(
+                    clap::Arg::new(ARG_HIPPO_URL)
+                        .required(true)
+                        .long("hippo-url")
+                        .env("HIPPO_URL")
+                        .about("The Hippo service to store credentials for"),
+                )
+                .arg(
+                    clap::Arg::new(ARG_HIPPO_USERNAME)
+                        .required(true)
+                        .long("hippo-username")
+                        .env("HIPPO_USERNAME")
+                        .about("The username for connecting to Hippo"),
+                )
+                .arg(
+                    clap::Arg::new(ARG_LOGIN_PASSWORD)
+                        .required(true)
+                        .long("password")
+                        .env("HIPPO_PASSWORD")
+                        .about("The password to store"),
+                ),
+        )
         .arg(
             clap::Arg::new(ARG_HIPPOFACTS)
                 .required(true)
@@ -66,7 +104,7 @@ async fn main() -> anyhow::Result<()> {
         )
         .arg(
             clap::Arg::new(ARG_BINDLE_URL)
-                .required_if_eq_any(&[(ARG_ACTION, ACTION_ALL), (ARG_ACTION, ACTION_BINDLE)])
+                .required_if_eq_any(&[(ARG_ACTION, ACTION_ALL), (ARG_ACTION, ACTION_BINDLE), (ARG_ACTION, ACTION_WATCH)])
                 .short('s')
                 .long("server")
                 .env("BINDLE_URL")
@@ -74,28 +112,27 @@ async fn main() -> anyhow::Result<()> {
         )
         .arg(
             clap::Arg::new(ARG_HIPPO_URL)
-                .required_if_eq(ARG_ACTION, ACTION_ALL)
+                .required_if_eq_any(&[(ARG_ACTION, ACTION_ALL), (ARG_ACTION, ACTION_WATCH)])
                 .long("hippo-url")
                 .env("HIPPO_URL")
                 .about("The Hippo service to push the artifacts to")
         )
         .arg(
             clap::Arg::new(ARG_HIPPO_USERNAME)
-                .required_if_eq(ARG_ACTION, ACTION_ALL)
+                .required_if_eq_any(&[(ARG_ACTION, ACTION_ALL), (ARG_ACTION, ACTION_WATCH)])
                 .long("hippo-username")
                 .env("HIPPO_USERNAME")
                 .about("The username for connecting to Hippo")
         )
         .arg(
             clap::Arg::new(ARG_HIPPO_PASSWORD)
-                .required_if_eq(ARG_ACTION, ACTION_ALL)
                 .long("hippo-password")
                 .env("HIPPO_PASSWORD")
-                .about("The username for connecting to Hippo")
+                .about("The password for connecting to Hippo (if not given, looked up in the OS keyring; see `hippo login`)")
         )
         .arg(
             clap::Arg::new(ARG_ACTION)
-                .possible_values(&[ACTION_ALL, ACTION_BINDLE, ACTION_PREPARE])
+                .possible_values(&[ACTION_ALL, ACTION_BINDLE, ACTION_PREPARE, ACTION_WATCH, ACTION_VALIDATE, ACTION_SERVE])
                 .default_value(ACTION_ALL)
                 .required(false)
                 .short('a')
@@ -110,8 +147,71 @@ async fn main() -> anyhow::Result<()> {
                 .long("insecure")
                 .about("If set, ignore server certificate errors"),
         )
+        .arg(
+            clap::Arg::new(ARG_UPLOAD_CONCURRENCY)
+                .required(false)
+                .takes_value(true)
+                .default_value("4")
+                .long("upload-concurrency")
+                .about("How many parcels to upload at once"),
+        )
+        .arg(
+            clap::Arg::new(ARG_SIGN_WITH)
+                .required(false)
+                .takes_value(true)
+                .long("sign-with")
+                .about("A keyfile to sign the generated invoice with"),
+        )
+        .arg(
+            clap::Arg::new(ARG_SIGNING_ROLE)
+                .possible_values(&["creator", "approver", "host", "proxy"])
+                .default_value("creator")
+                .required(false)
+                .takes_value(true)
+                .long("signing-role")
+                .about("The role to sign the generated invoice with"),
+        )
+        .arg(
+            clap::Arg::new(ARG_TRUSTED_KEYS)
+                .required(false)
+                .takes_value(true)
+                .long("trusted-keys")
+                .about("A file of public keys trusted to sign external invoice references"),
+        )
+        .arg(
+            clap::Arg::new(ARG_LISTEN)
+                .required(false)
+                .takes_value(true)
+                .default_value("127.0.0.1:7467")
+                .long("listen")
+                .about("The address to listen on with --action serve"),
+        )
+        .arg(
+            clap::Arg::new(ARG_TLS_CERT)
+                .required(false)
+                .takes_value(true)
+                .long("tls-cert")
+                .requires(ARG_TLS_KEY)
+                .about("A TLS certificate to serve over HTTPS with --action serve"),
+        )
+        .arg(
+            clap::Arg::new(ARG_TLS_KEY)
+                .required(false)
+                .takes_value(true)
+                .long("tls-key")
+                .requires(ARG_TLS_CERT)
+                .about("The private key matching --tls-cert"),
+        )
         .get_matches();
 
+    if let Some(login_args) = args.subcommand_matches(SUBCOMMAND_LOGIN) {
+        return hippo_login(
+            login_args.value_of(ARG_HIPPO_URL).unwrap(),
+            login_args.value_of(ARG_HIPPO_USERNAME).unwrap(),
+            login_args.value_of(ARG_LOGIN_PASSWORD).unwrap(),
+        );
+    }
+
     let hippofacts_arg = args
         .value_of(ARG_HIPPOFACTS)
         .ok_or_else(|| anyhow::Error::msg("HIPPOFACTS file is required"))?;
@@ -120,22 +220,38 @@ async fn main() -> anyhow::Result<()> {
     let output_format_arg = args.value_of(ARG_OUTPUT).unwrap();
     let bindle_url = args.value_of(ARG_BINDLE_URL).map(|s| s.to_owned());
     let bindle_settings = match args.value_of(ARG_ACTION) {
-        None | Some(ACTION_PREPARE) => BindleSettings::NoPush(bindle_url),
+        None | Some(ACTION_PREPARE) | Some(ACTION_VALIDATE) | Some(ACTION_SERVE) => BindleSettings::NoPush(bindle_url),
         _ => BindleSettings::Push(bindle_url.ok_or_else(|| anyhow::anyhow!("Bindle URL must be set for this action"))?),
     };
     let hippo_url = match args.value_of(ARG_ACTION) {
-        Some(ACTION_ALL) => args.value_of(ARG_HIPPO_URL).map(|s| s.to_owned()),
+        Some(ACTION_ALL) | Some(ACTION_WATCH) => args.value_of(ARG_HIPPO_URL).map(|s| s.to_owned()),
         _ => None,
     };
     let hippo_username = args.value_of(ARG_HIPPO_USERNAME);
-    let hippo_password = args.value_of(ARG_HIPPO_PASSWORD);
+    let hippo_password = args.value_of(ARG_HIPPO_PASSWORD).map(|s| s.to_owned());
 
-    let notify_to = hippo_url.map(|url| hippo_notifier::ConnectionInfo {
-        url,
-        danger_accept_invalid_certs: args.is_present(ARG_INSECURE),
-        username: hippo_username.unwrap().to_owned(), // Known to be set if the URL is
-        password: hippo_password.unwrap().to_owned(),
-    });
+    let notify_to = match hippo_url {
+        Some(url) => {
+            let username = hippo_username.unwrap().to_owned(); // Known to be set if the URL is
+            let password = match hippo_password {
+                Some(password) => password,
+                None => keyring_password(&url, &username)?.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "No Hippo password given and none found in the keyring for {} ({}); pass --hippo-password or run `hippo login`",
+                        username,
+                        url
+                    )
+                })?,
+            };
+            Some(ConnectionInfo {
+                url,
+                danger_accept_invalid_certs: args.is_present(ARG_INSECURE),
+                username,
+                password,
+            })
+        }
+        None => None,
+    };
 
     let source_file_or_dir = std::env::current_dir()?.join(hippofacts_arg);
     let source = if source_file_or_dir.is_file() {
@@ -150,114 +266,249 @@ async fn main() -> anyhow::Result<()> {
         ));
     }
 
+    if args.value_of(ARG_ACTION) == Some(ACTION_VALIDATE) {
+        return validate(&source, bindle_settings.bindle_url().as_deref());
+    }
+
     let destination = match staging_dir_arg {
         Some(dir) => std::env::current_dir()?.join(dir),
         None => std::env::temp_dir().join("hippo-staging"), // TODO: make unpredictable?
     };
     let invoice_versioning = InvoiceVersioning::parse(versioning_arg);
     let output_format = OutputFormat::parse(output_format_arg);
+    let upload_concurrency = args
+        .value_of(ARG_UPLOAD_CONCURRENCY)
+        .unwrap()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("--upload-concurrency must be a number"))?;
+    let signing_config = match args.value_of(ARG_SIGN_WITH) {
+        Some(keyfile) => Some(SigningConfig {
+            keyfile: std::env::current_dir()?.join(keyfile),
+            role: SigningConfig::parse_role(args.value_of(ARG_SIGNING_ROLE).unwrap())?,
+        }),
+        None => None,
+    };
+    let trusted_keys = args.value_of(ARG_TRUSTED_KEYS).map(|path| std::env::current_dir().map(|dir| dir.join(path))).transpose()?;
 
     run(
         &source,
         &destination,
         invoice_versioning,
         output_format,
-        bindle_settings,
-        notify_to,
+        bindle_settings.clone(),
+        notify_to.clone(),
+        upload_concurrency,
+        signing_config.clone(),
+        trusted_keys.clone(),
     )
-    .await
+    .await?;
+
+    if args.value_of(ARG_ACTION) == Some(ACTION_WATCH) {
+        watch(
+            &source,
+            &destination,
+            invoice_versioning,
+            output_format,
+            bindle_settings,
+            notify_to,
+            upload_concurrency,
+            signing_config,
+            trusted_keys,
+        )
+        .await?;
+    }
+
+    if args.value_of(ARG_ACTION) == Some(ACTION_SERVE) {
+        let listen: std::net::SocketAddr = args
+            .value_of(ARG_LISTEN)
+            .unwrap()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("--listen must be a host:port address"))?;
+        let tls = match (args.value_of(ARG_TLS_CERT), args.value_of(ARG_TLS_KEY)) {
+            (Some(cert), Some(key)) => Some(hippo_core::TlsConfig {
+                cert_path: std::env::current_dir()?.join(cert),
+                key_path: std::env::current_dir()?.join(key),
+            }),
+            _ => None,
+        };
+        tokio::task::spawn_blocking(move || hippo_core::serve(destination, listen, tls)).await??;
+    }
+
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run(
     source: impl AsRef<std::path::Path>,
     destination: impl AsRef<std::path::Path>,
     invoice_versioning: InvoiceVersioning,
     output_format: OutputFormat,
     bindle_settings: BindleSettings,
-    notify_to: Option<hippo_notifier::ConnectionInfo>,
+    notify_to: Option<ConnectionInfo>,
+    upload_concurrency: usize,
+    signing_config: Option<SigningConfig>,
+    trusted_keys: Option<std::path::PathBuf>,
+) -> anyhow::Result<()> {
+    let mut request = BuildRequest::new(source.as_ref(), destination.as_ref())
+        .invoice_versioning(invoice_versioning)
+        .bindle_settings(bindle_settings)
+        .upload_concurrency(upload_concurrency);
+    if let Some(connection) = notify_to {
+        request = request.connect_to(connection);
+    }
+    if let Some(signing) = signing_config {
+        request = request.sign_with(signing);
+    }
+    if let Some(trusted_keys) = trusted_keys {
+        request = request.trust_keys(trusted_keys);
+    }
+
+    let result = request.run().await?;
+
+    // TODO: handle case where push succeeded but notify failed
+    match output_format {
+        OutputFormat::None => (),
+        OutputFormat::Id => println!("{}", result.id),
+        OutputFormat::Message if result.pushed => println!("pushed: {}", result.id),
+        OutputFormat::Message => {
+            println!("id:      {}", result.id);
+            println!(
+                "command: bindle push -p {} {}",
+                dunce::canonicalize(&destination)?.to_string_lossy(),
+                result.id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps `source` and every glob root it references under watch, re-running
+/// `run` on each debounced burst of changes. Runs until the process is killed;
+/// a failed rebuild is reported but doesn't stop the watch.
+#[allow(clippy::too_many_arguments)]
+async fn watch(
+    source: impl AsRef<std::path::Path>,
+    destination: impl AsRef<std::path::Path>,
+    invoice_versioning: InvoiceVersioning,
+    output_format: OutputFormat,
+    bindle_settings: BindleSettings,
+    notify_to: Option<ConnectionInfo>,
+    upload_concurrency: usize,
+    signing_config: Option<SigningConfig>,
+    trusted_keys: Option<std::path::PathBuf>,
 ) -> anyhow::Result<()> {
-    let spec = HippoFacts::read_from(&source)?;
+    use notify::Watcher;
 
+    let source = source.as_ref();
+    let destination = destination.as_ref();
     let source_dir = source
-        .as_ref()
         .parent()
         .ok_or_else(|| anyhow::Error::msg("Can't establish source directory"))?
         .to_path_buf();
 
-    // Do this outside the `expand` function so `expand` is more testable
-    let external_invoices = prefetch_required_invoices(&spec, bindle_settings.bindle_url()).await?;
-
-    let expansion_context = ExpansionContext {
-        relative_to: source_dir.clone(),
-        invoice_versioning,
-        external_invoices,
-    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(source, notify::RecursiveMode::NonRecursive)?;
+    for root in hippo_core::glob_roots(source)? {
+        if root.exists() {
+            watcher.watch(&root, notify::RecursiveMode::Recursive)?;
+        }
+    }
 
-    let invoice = expander::expand(&spec, &expansion_context)?;
+    // `notify`'s receiver is a blocking `std::sync::mpsc::Receiver`; bridge it
+    // onto a blocking task and forward events through a tokio channel so this
+    // loop can `.await` them instead of parking a tokio worker thread for as
+    // long as `watch` runs.
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::task::spawn_blocking(move || {
+        while let Ok(event) = rx.recv() {
+            if events_tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
 
-    let writer = BindleWriter::new(&source_dir, &destination);
-    writer.write(&invoice).await?;
+    println!("watching {} for changes", source_dir.to_string_lossy());
 
-    if let BindleSettings::Push(url) = &&bindle_settings {
-        bindle_pusher::push_all(&destination, &invoice.bindle.id, &url).await?;
-        if let Some(hippo_url) = &notify_to {
-            hippo_notifier::register(&invoice.bindle.id, &hippo_url).await?;
-        }
-    }
+    while events_rx.recv().await.is_some() {
+        // Coalesce the rest of this burst (e.g. an editor's save-and-rename)
+        // into the rebuild we're about to do.
+        while tokio::time::timeout(WATCH_DEBOUNCE, events_rx.recv()).await.is_ok() {}
 
-    // TODO: handle case where push succeeded but notify failed
-    match output_format {
-        OutputFormat::None => (),
-        OutputFormat::Id => println!("{}", &invoice.bindle.id),
-        OutputFormat::Message => match &bindle_settings {
-            BindleSettings::Push(_) =>
-                println!("pushed: {}", &invoice.bindle.id),
-            BindleSettings::NoPush(_) => {
-                println!("id:      {}", &invoice.bindle.id);
-                println!(
-                    "command: bindle push -p {} {}",
-                    dunce::canonicalize(&destination)?.to_string_lossy(),
-                    &invoice.bindle.id
-                );
-            },
+        match run(
+            source,
+            destination,
+            invoice_versioning,
+            output_format,
+            bindle_settings.clone(),
+            notify_to.clone(),
+            upload_concurrency,
+            signing_config.clone(),
+            trusted_keys.clone(),
+        )
+        .await
+        {
+            Ok(()) => (),
+            Err(e) => eprintln!("rebuild failed: {}", e),
         }
     }
 
     Ok(())
 }
 
-async fn prefetch_required_invoices(
-    hippofacts: &HippoFacts,
-    bindle_url: Option<String>,
-) -> anyhow::Result<HashMap<bindle::Id, bindle::Invoice>> {
-    let mut map = HashMap::new();
+/// Handles `--action validate`: lints the spec and prints every diagnostic
+/// found, exiting nonzero if any of them are errors.
+fn validate(source: &std::path::Path, bindle_url: Option<&str>) -> anyhow::Result<()> {
+    let diagnostics = hippo_core::validate(source, bindle_url)?;
 
-    let external_refs: Vec<bindle::Id> = hippofacts
-        .entries
+    for diagnostic in &diagnostics {
+        let severity = match diagnostic.severity {
+            hippo_core::Severity::Error => "error",
+            hippo_core::Severity::Warning => "warning",
+        };
+        println!("{}: {}: {}", severity, diagnostic.location, diagnostic.message);
+    }
+
+    let error_count = diagnostics
         .iter()
-        .flat_map(external_bindle_id)
-        .collect();
-    if external_refs.is_empty() {
-        return Ok(map);
+        .filter(|d| d.severity == hippo_core::Severity::Error)
+        .count();
+    if error_count > 0 {
+        std::process::exit(1);
     }
 
-    let base_url = bindle_url.as_ref().ok_or_else(|| {
-        anyhow::anyhow!("Spec file contains external references but Bindle server URL is not set")
-    })?;
-    let client = bindle::client::Client::new(base_url)?;
+    Ok(())
+}
 
-    for external_ref in external_refs {
-        let invoice = client.get_yanked_invoice(&external_ref).await?;
-        map.insert(external_ref, invoice);
+/// Looks up a previously-stored Hippo password in the OS keyring, keyed by
+/// the Hippo URL and username. Returns `None` rather than an error when there
+/// simply isn't an entry, so callers can fall back to requiring `--hippo-password`.
+fn keyring_password(hippo_url: &str, hippo_username: &str) -> anyhow::Result<Option<String>> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, &keyring_key(hippo_url, hippo_username));
+    match entry.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Can't read keyring entry: {}", e)),
     }
+}
 
-    Ok(map)
+fn keyring_key(hippo_url: &str, hippo_username: &str) -> String {
+    format!("{}:{}", hippo_url, hippo_username)
 }
 
-fn external_bindle_id(entry: &HippoFactsEntry) -> Option<bindle::Id> {
-    entry.external_ref().map(|ext| ext.bindle_id.clone())
+/// Handles `hippo login`: stores (or updates) a Hippo password in the OS
+/// keyring so it doesn't need to be passed via flag or env var afterwards.
+fn hippo_login(hippo_url: &str, hippo_username: &str, password: &str) -> anyhow::Result<()> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, &keyring_key(hippo_url, hippo_username));
+    entry
+        .set_password(password)
+        .with_context(|| "Can't store password in the OS keyring")?;
+    println!("Stored credentials for {} on {}", hippo_username, hippo_url);
+    Ok(())
 }
 
+#[derive(Clone, Copy)]
 enum OutputFormat {
     None,
     Id,
@@ -275,17 +526,3 @@ impl OutputFormat {
         }
     }
 }
-
-enum BindleSettings {
-    NoPush(Option<String>),
-    Push(String),
-}
-
-impl BindleSettings {
-    pub fn bindle_url(&self) -> Option<String> {
-        match self {
-            Self::NoPush(opt) => opt.clone(),
-            Self::Push(url) => Some(url.clone()),
-        }
-    }
-}
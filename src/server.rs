@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::bindle_utils;
+
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Serves a staged bindle directory over HTTP(S), exposing the two Bindle
+/// read endpoints a runtime needs to pull an invoice and its parcels:
+/// `GET /v1/_i/{id}` for the invoice and `GET /v1/_i/{id}@{sha256}` for a
+/// parcel, whose content is hashed on the way out and rejected if it doesn't
+/// match the requested sha. Blocks the calling thread; run it on a blocking
+/// task if you need the async runtime free.
+pub fn serve(destination: impl AsRef<Path>, listen: SocketAddr, tls: Option<TlsConfig>) -> anyhow::Result<()> {
+    let destination = destination.as_ref().to_path_buf();
+
+    let server = match tls {
+        Some(tls) => {
+            let config = tiny_http::SslConfig {
+                certificate: std::fs::read(&tls.cert_path)?,
+                private_key: std::fs::read(&tls.key_path)?,
+            };
+            tiny_http::Server::https(listen, config).map_err(|e| anyhow::anyhow!("Can't start HTTPS server: {}", e))?
+        }
+        None => tiny_http::Server::http(listen).map_err(|e| anyhow::anyhow!("Can't start HTTP server: {}", e))?,
+    };
+
+    println!("serving staged bindle at {} on {}", destination.display(), listen);
+
+    for request in server.incoming_requests() {
+        if let Err(e) = handle(&destination, request) {
+            eprintln!("request failed: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(destination: &Path, request: tiny_http::Request) -> anyhow::Result<()> {
+    let path = request.url().trim_start_matches("/v1/_i/").to_owned();
+
+    if let Some((_, sha256)) = path.split_once('@') {
+        if !is_valid_sha256(sha256) {
+            return request.respond(tiny_http::Response::empty(400)).map_err(Into::into);
+        }
+        return match read_parcel(destination, sha256) {
+            Ok(bytes) => request.respond(tiny_http::Response::from_data(bytes)).map_err(Into::into),
+            Err(_) => request.respond(tiny_http::Response::empty(404)).map_err(Into::into),
+        };
+    }
+
+    match std::fs::read_to_string(destination.join(bindle_utils::INVOICE_FILE)) {
+        Ok(invoice) => request.respond(tiny_http::Response::from_string(invoice)).map_err(Into::into),
+        Err(_) => request.respond(tiny_http::Response::empty(404)).map_err(Into::into),
+    }
+}
+
+/// Whether `sha256` is a well-formed lowercase hex SHA-256 digest. Rejecting
+/// anything else before it reaches `bindle_utils::parcel_path` keeps a request
+/// like `/v1/_i/x@../../../etc/passwd` from being treated as a parcel hash and
+/// walking the staged parcels directory.
+fn is_valid_sha256(sha256: &str) -> bool {
+    sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn read_parcel(destination: &Path, sha256: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = std::fs::read(bindle_utils::parcel_path(destination, sha256))?;
+    let actual = bindle_utils::sha256_digest(&bytes);
+    if actual != sha256 {
+        return Err(anyhow::anyhow!("Parcel {} is corrupt on disk (hash mismatch)", sha256));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_a_well_formed_digest() {
+        assert!(is_valid_sha256(&"a".repeat(64)));
+        assert!(is_valid_sha256(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        ));
+    }
+
+    #[test]
+    fn rejects_path_traversal() {
+        assert!(!is_valid_sha256("../../../../etc/passwd"));
+        assert!(!is_valid_sha256("x/../../../etc/passwd"));
+    }
+
+    #[test]
+    fn rejects_uppercase_hex() {
+        assert!(!is_valid_sha256(&"A".repeat(64)));
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        assert!(!is_valid_sha256(&"a".repeat(63)));
+        assert!(!is_valid_sha256(&"a".repeat(65)));
+        assert!(!is_valid_sha256(""));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::hippofacts::HippoFacts;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Lints a parsed HIPPOFACTS spec without expanding or writing a bindle.
+/// Collects every problem it finds rather than stopping at the first one, so
+/// a single run surfaces everything wrong with the spec.
+pub fn validate(spec: &HippoFacts, relative_to: &Path, bindle_url: Option<&str>) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut parcel_names = HashSet::new();
+    let entry_names: HashSet<&str> = spec.entries.iter().map(|e| e.name.as_str()).collect();
+
+    for entry in &spec.entries {
+        if let Some(external) = &entry.external {
+            if bindle_url.is_none() {
+                diagnostics.push(Diagnostic::error(
+                    entry.name.clone(),
+                    format!(
+                        "references external bindle {} but no Bindle server URL is configured",
+                        external.bindle_id
+                    ),
+                ));
+            }
+        }
+
+        for required in entry.requires.iter().flatten() {
+            if !entry_names.contains(required.as_str()) {
+                diagnostics.push(Diagnostic::error(
+                    entry.name.clone(),
+                    format!("requires entry \"{}\", which doesn't exist in this spec", required),
+                ));
+            }
+        }
+
+        for pattern in entry.files.iter().flatten() {
+            let full_pattern = relative_to.join(pattern);
+            let matches = match glob::glob(&full_pattern.to_string_lossy()) {
+                Ok(paths) => paths.filter_map(Result::ok).filter(|p| p.is_file()).collect::<Vec<_>>(),
+                Err(e) => {
+                    diagnostics.push(Diagnostic::error(entry.name.clone(), format!("invalid glob \"{}\": {}", pattern, e)));
+                    continue;
+                }
+            };
+
+            if matches.is_empty() {
+                diagnostics.push(Diagnostic::error(
+                    entry.name.clone(),
+                    format!("pattern \"{}\" matched no files", pattern),
+                ));
+                continue;
+            }
+
+            for path in matches {
+                let escapes = match (path.canonicalize(), relative_to.canonicalize()) {
+                    (Ok(canonical_path), Ok(canonical_root)) => !canonical_path.starts_with(canonical_root),
+                    _ => false,
+                };
+                if escapes {
+                    diagnostics.push(Diagnostic::error(
+                        entry.name.clone(),
+                        format!("\"{}\" resolves to a path outside the spec's directory", path.display()),
+                    ));
+                    continue;
+                }
+
+                if let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) {
+                    if !parcel_names.insert(name.clone()) {
+                        diagnostics.push(Diagnostic::error(
+                            entry.name.clone(),
+                            format!("parcel name \"{}\" is staged more than once", name),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hippofacts::{BindleSpec, ExternalRef, HippoFactsEntry};
+
+    fn spec(entries: Vec<HippoFactsEntry>) -> HippoFacts {
+        HippoFacts {
+            bindle: BindleSpec {
+                name: "test".to_owned(),
+                version: "0.1.0".to_owned(),
+            },
+            annotations: None,
+            entries,
+        }
+    }
+
+    fn entry(name: &str) -> HippoFactsEntry {
+        HippoFactsEntry {
+            name: name.to_owned(),
+            route: None,
+            files: None,
+            external: None,
+            requires: None,
+        }
+    }
+
+    fn temp_dir(label: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("hippo-validator-test-{}-{}", label, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn external_ref_without_bindle_url_is_an_error() {
+        let mut external = entry("frontend");
+        external.external = Some(ExternalRef {
+            bindle_id: bindle::Id::try_from("example.com/frontend/1.0.0".to_owned()).unwrap(),
+        });
+        let spec = spec(vec![external]);
+
+        let diagnostics = validate(&spec, &temp_dir("external"), None);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(diagnostics[0].message.contains("no Bindle server URL"));
+    }
+
+    #[test]
+    fn external_ref_with_bindle_url_is_fine() {
+        let mut external = entry("frontend");
+        external.external = Some(ExternalRef {
+            bindle_id: bindle::Id::try_from("example.com/frontend/1.0.0".to_owned()).unwrap(),
+        });
+        let spec = spec(vec![external]);
+
+        let diagnostics = validate(&spec, &temp_dir("external-ok"), Some("https://bindle.example.com"));
+
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn unresolved_requires_is_an_error() {
+        let mut web = entry("web");
+        web.requires = Some(vec!["does-not-exist".to_owned()]);
+        let spec = spec(vec![web]);
+
+        let diagnostics = validate(&spec, &temp_dir("requires"), None);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("does-not-exist"));
+    }
+
+    #[test]
+    fn requires_resolving_to_another_entry_is_fine() {
+        let mut web = entry("web");
+        web.requires = Some(vec!["api".to_owned()]);
+        let spec = spec(vec![web, entry("api")]);
+
+        let diagnostics = validate(&spec, &temp_dir("requires-ok"), None);
+
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn glob_matching_no_files_is_an_error() {
+        let mut web = entry("web");
+        web.files = Some(vec!["*.nope".to_owned()]);
+        let spec = spec(vec![web]);
+
+        let diagnostics = validate(&spec, &temp_dir("no-match"), None);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("matched no files"));
+    }
+
+    #[test]
+    fn duplicate_parcel_names_across_entries_is_an_error() {
+        let dir = temp_dir("dup");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("a").join("same.txt"), "a").unwrap();
+        std::fs::write(dir.join("b").join("same.txt"), "b").unwrap();
+
+        let mut first = entry("first");
+        first.files = Some(vec!["a/same.txt".to_owned()]);
+        let mut second = entry("second");
+        second.files = Some(vec!["b/same.txt".to_owned()]);
+        let spec = spec(vec![first, second]);
+
+        let diagnostics = validate(&spec, &dir, None);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("same.txt"));
+    }
+}